@@ -1,8 +1,27 @@
 //! Asynchronous notifications.
 
 use fallible_iterator::{FallibleIterator, IntoFallibleIterator};
+use std::collections::{HashSet, VecDeque};
 use std::fmt;
-use std::time::Duration;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Arc;
+#[cfg(all(unix, feature = "with-futures"))]
+use std::sync::Mutex;
+#[cfg(all(unix, feature = "with-futures"))]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(all(unix, feature = "with-futures"))]
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(unix)]
+use libc;
+
+#[cfg(feature = "with-futures")]
+use futures::{Async, Poll, Stream as FuturesStream};
+#[cfg(feature = "with-futures")]
+use futures::task::{self, Task};
 
 use {desynchronized, Result, Connection, NotificationsNew};
 use message::Backend;
@@ -56,9 +75,19 @@ impl<'conn> Notifications<'conn> {
     /// Returns a fallible iterator over notifications that blocks until one is
     /// received if none are pending.
     ///
-    /// The iterator will never return `None`.
+    /// The iterator will never return `None` on its own. The one exception
+    /// is a Unix-only interrupt set up via `BlockingIter::interrupt_handle`:
+    /// if `NotificationInterrupt::interrupt` is called while `next()` is
+    /// blocked (or before the next call), that call returns `Ok(None)`
+    /// instead of blocking forever, and `BlockingIter::is_interrupted`
+    /// reports `true` for it.
     pub fn blocking_iter<'a>(&'a self) -> BlockingIter<'a> {
-        BlockingIter { conn: self.conn }
+        BlockingIter {
+            conn: self.conn,
+            #[cfg(unix)]
+            interrupt: None,
+            interrupted: false,
+        }
     }
 
     /// Returns a fallible iterator over notifications that blocks for a limited
@@ -74,6 +103,171 @@ impl<'conn> Notifications<'conn> {
             timeout: timeout,
         }
     }
+
+    /// Returns a `futures::Stream` over notifications that parks the
+    /// current task instead of blocking a thread while none are pending.
+    ///
+    /// At most one helper thread is parked on the connection's socket
+    /// becoming readable at a time, regardless of how many times `poll` is
+    /// called before it's ready; it wakes the most recently polled task.
+    /// Only available on Unix, since it relies on the socket's raw file
+    /// descriptor and the self-pipe trick used to cancel that thread
+    /// promptly if the `Stream` is dropped while it's parked.
+    #[cfg(all(unix, feature = "with-futures"))]
+    pub fn stream<'a>(&'a self) -> Result<Stream<'a>> {
+        let (read_fd, write_fd) = create_self_pipe().map_err(Error::Io)?;
+        Ok(Stream {
+            conn: self.conn,
+            waiter: Arc::new(StreamWaiter {
+                task: Mutex::new(None),
+                parked: AtomicBool::new(false),
+                cancel: SelfPipe {
+                    read: Arc::new(PipeEnd(read_fd)),
+                    write: Arc::new(PipeEnd(write_fd)),
+                },
+            }),
+        })
+    }
+
+    /// Subscribes to a set of channels, returning a handle that receives a
+    /// copy of every notification raised on them.
+    ///
+    /// Unlike `iter`/`blocking_iter`/`timeout_iter`, which share a single
+    /// FIFO across all callers, every `Subscriber` gets its own copy of each
+    /// matching notification, so multiple independent consumers can fan out
+    /// from the same connection. Each subscriber buffers up to 256
+    /// notifications and drops the oldest one on overflow; use
+    /// `subscribe_with` to customize this.
+    pub fn subscribe<I>(&self, channels: I) -> Subscriber<'conn>
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.subscribe_with(channels, 256, OverflowPolicy::DropOldest)
+    }
+
+    /// Like `subscribe`, but with an explicit buffer capacity and overflow
+    /// policy.
+    pub fn subscribe_with<I>(
+        &self,
+        channels: I,
+        capacity: usize,
+        policy: OverflowPolicy,
+    ) -> Subscriber<'conn>
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let channels = channels.into_iter().map(Into::into).collect();
+        let id = self.conn.conn.borrow_mut().subscribers.register(
+            channels,
+            capacity,
+            policy,
+        );
+        Subscriber {
+            conn: self.conn,
+            id: id,
+        }
+    }
+
+    /// Blocks until every notification the backend had already queued at
+    /// the moment of the call has been pulled into the local buffer.
+    ///
+    /// This gives callers a deterministic "all caught up" point, which is
+    /// invaluable in tests and when coordinating a clean shutdown. It's
+    /// implemented by issuing a trivial round-trip query and draining every
+    /// message up to and including its `ReadyForQuery`, appending any
+    /// `NotificationResponse`s seen along the way onto the local queue.
+    /// Because the backend emits messages strictly in order, once
+    /// `ReadyForQuery` for this query arrives, every `NOTIFY` the backend
+    /// had already queued is guaranteed to have been received.
+    pub fn barrier(&self) -> Result<()> {
+        self.run_barrier(None)
+    }
+
+    /// Returns a fallible iterator over notifications that polls until one
+    /// is received or `deadline` passes.
+    ///
+    /// Unlike `timeout_iter`, which takes a fixed `Duration` per call and
+    /// returns `None` as soon as a single read comes back empty, this
+    /// iterator recomputes the remaining time after every wakeup that
+    /// doesn't deliver a notification and keeps polling until `deadline` itself is
+    /// reached, so a caller with an overall deadline doesn't need to track
+    /// remaining time itself across calls.
+    pub fn deadline_iter<'a>(&'a self, deadline: Instant) -> DeadlineIter<'a> {
+        DeadlineIter {
+            conn: self.conn,
+            deadline: deadline,
+        }
+    }
+
+    /// Like `barrier`, but returns an error with `io::ErrorKind::TimedOut`
+    /// if the round-trip doesn't complete within `timeout`, rather than
+    /// blocking indefinitely.
+    pub fn barrier_timeout(&self, timeout: Duration) -> Result<()> {
+        self.run_barrier(Some(timeout))
+    }
+
+    fn run_barrier(&self, timeout: Option<Duration>) -> Result<()> {
+        let mut conn = self.conn.conn.borrow_mut();
+
+        if conn.is_desynchronized() {
+            return Err(Error::Io(desynchronized()));
+        }
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        conn.send_simple_query("").map_err(Error::Io)?;
+
+        loop {
+            // With a deadline, every read is individually bounded by the
+            // time remaining, so a server that never replies surfaces as a
+            // `TimedOut` error instead of blocking here forever. Without
+            // one, fall back to a plain blocking read.
+            let message = match deadline {
+                Some(deadline) => {
+                    let remaining = PollTimeout::until(deadline);
+                    if remaining.is_immediate() {
+                        return Err(Error::Io(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "timed out waiting for the notification barrier to complete",
+                        )));
+                    }
+                    match conn.read_message_with_notification_timeout(remaining.duration()) {
+                        Ok(Some(message)) => message,
+                        Ok(None) => continue,
+                        Err(err) => return Err(Error::Io(err)),
+                    }
+                }
+                None => match conn.read_message_with_notification() {
+                    Ok(message) => message,
+                    Err(err) => return Err(Error::Io(err)),
+                },
+            };
+
+            match message {
+                Backend::NotificationResponse { process_id, channel, payload } => {
+                    let notification = Notification {
+                        process_id: process_id,
+                        channel: channel,
+                        payload: payload,
+                    };
+                    conn.subscribers.dispatch(&notification);
+                    conn.notifications.push_back(notification);
+                }
+                // Every other message here is a reply to the barrier's own
+                // trivial query. That's safe to assume because this crate's
+                // connections only ever have one statement in flight at a
+                // time (enforced by `is_desynchronized`'s check above), so
+                // nothing but the barrier's own query can be replying right
+                // now; these replies aren't part of any statement the
+                // caller cares about, so they're discarded rather than
+                // forwarded anywhere.
+                Backend::ReadyForQuery { .. } => return Ok(()),
+                _ => {}
+            }
+        }
+    }
 }
 
 impl<'a, 'conn> IntoFallibleIterator for &'a Notifications<'conn> {
@@ -114,11 +308,13 @@ impl<'a> FallibleIterator for Iter<'a> {
 
         match conn.read_message_with_notification_nonblocking() {
             Ok(Some(Backend::NotificationResponse { process_id, channel, payload })) => {
-                Ok(Some(Notification {
+                let notification = Notification {
                     process_id: process_id,
                     channel: channel,
                     payload: payload,
-                }))
+                };
+                conn.subscribers.dispatch(&notification);
+                Ok(Some(notification))
             }
             Ok(None) => Ok(None),
             Err(err) => Err(Error::Io(err)),
@@ -131,9 +327,66 @@ impl<'a> FallibleIterator for Iter<'a> {
     }
 }
 
+/// A self-pipe pair backing one `BlockingIter`'s interrupt support: the read
+/// end is polled alongside the connection's socket, the write end is handed
+/// out (and clonable) as `NotificationInterrupt`.
+#[cfg(unix)]
+struct SelfPipe {
+    read: Arc<PipeEnd>,
+    write: Arc<PipeEnd>,
+}
+
 /// An iterator over notifications which will block if none are pending.
 pub struct BlockingIter<'a> {
     conn: &'a Connection,
+    #[cfg(unix)]
+    interrupt: Option<SelfPipe>,
+    interrupted: bool,
+}
+
+#[cfg(unix)]
+impl<'a> BlockingIter<'a> {
+    /// Returns a cloneable handle that can interrupt an in-progress or
+    /// future call to `next()`.
+    ///
+    /// Implemented with the self-pipe trick: a pipe is created the first
+    /// time this is called, and `next()` polls its read end alongside the
+    /// connection's socket via `poll(2)`. Calling
+    /// `NotificationInterrupt::interrupt` writes a single byte to the write
+    /// end, waking a blocked `next()` promptly instead of leaving it parked
+    /// forever. Calling this more than once on the same iterator is fine:
+    /// later calls just hand out another handle onto the same underlying
+    /// pipe.
+    pub fn interrupt_handle(&mut self) -> Result<NotificationInterrupt> {
+        if self.interrupt.is_none() {
+            let (read_fd, write_fd) = create_self_pipe().map_err(Error::Io)?;
+            self.interrupt = Some(SelfPipe {
+                read: Arc::new(PipeEnd(read_fd)),
+                write: Arc::new(PipeEnd(write_fd)),
+            });
+        }
+
+        let pipe = self.interrupt.as_ref().unwrap();
+        Ok(NotificationInterrupt { write_fd: pipe.write.clone() })
+    }
+
+    /// Determines if the most recent call to `next()` returned `None`
+    /// because it was interrupted via a `NotificationInterrupt`, as opposed
+    /// to some other spurious wakeup.
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted
+    }
+}
+
+#[cfg(not(unix))]
+impl<'a> BlockingIter<'a> {
+    /// Determines if the most recent call to `next()` returned `None`.
+    ///
+    /// Always `false` on non-Unix targets, since interrupt support relies
+    /// on the self-pipe trick and isn't available there.
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted
+    }
 }
 
 impl<'a> FallibleIterator for BlockingIter<'a> {
@@ -141,6 +394,8 @@ impl<'a> FallibleIterator for BlockingIter<'a> {
     type Error = Error;
 
     fn next(&mut self) -> Result<Option<Notification>> {
+        self.interrupted = false;
+
         let mut conn = self.conn.conn.borrow_mut();
 
         if let Some(notification) = conn.notifications.pop_front() {
@@ -151,13 +406,78 @@ impl<'a> FallibleIterator for BlockingIter<'a> {
             return Err(Error::Io(desynchronized()));
         }
 
+        #[cfg(unix)]
+        {
+            if let Some(ref pipe) = self.interrupt {
+                let socket_fd = conn.as_raw_fd();
+                let pipe_fd = pipe.read.0;
+
+                loop {
+                    let mut fds = [
+                        libc::pollfd { fd: socket_fd, events: libc::POLLIN, revents: 0 },
+                        libc::pollfd { fd: pipe_fd, events: libc::POLLIN, revents: 0 },
+                    ];
+
+                    if unsafe { libc::poll(fds.as_mut_ptr(), 2, -1) } < 0 {
+                        let err = io::Error::last_os_error();
+                        if err.kind() == io::ErrorKind::Interrupted {
+                            continue;
+                        }
+                        return Err(Error::Io(err));
+                    }
+
+                    if fds[1].revents & libc::POLLIN != 0 {
+                        // Drain fully so a later poll doesn't spuriously
+                        // fire again on a byte we already consumed.
+                        drain_pipe(pipe_fd);
+                        self.interrupted = true;
+                        return Ok(None);
+                    }
+
+                    // POLLHUP/POLLERR/POLLNVAL fire on every poll once the
+                    // peer has closed the connection (or the fd is simply
+                    // bad) without ever setting POLLIN, so they have to be
+                    // checked and returned as errors here; otherwise this
+                    // loop would spin at 100% CPU forever instead of
+                    // falling through to the usual disconnect handling.
+                    if fds[0].revents & (libc::POLLHUP | libc::POLLERR | libc::POLLNVAL) != 0 {
+                        return Err(Error::Io(io::Error::new(
+                            io::ErrorKind::Other,
+                            "connection closed while waiting for a notification",
+                        )));
+                    }
+
+                    if fds[0].revents & libc::POLLIN != 0 {
+                        match conn.read_message_with_notification_nonblocking() {
+                            Ok(Some(Backend::NotificationResponse { process_id, channel, payload })) => {
+                                let notification = Notification {
+                                    process_id: process_id,
+                                    channel: channel,
+                                    payload: payload,
+                                };
+                                conn.subscribers.dispatch(&notification);
+                                return Ok(Some(notification));
+                            }
+                            // The socket was readable but didn't yield a
+                            // full message yet; poll again.
+                            Ok(None) => continue,
+                            Err(err) => return Err(Error::Io(err)),
+                            _ => unreachable!(),
+                        }
+                    }
+                }
+            }
+        }
+
         match conn.read_message_with_notification() {
             Ok(Backend::NotificationResponse { process_id, channel, payload }) => {
-                Ok(Some(Notification {
+                let notification = Notification {
                     process_id: process_id,
                     channel: channel,
                     payload: payload,
-                }))
+                };
+                conn.subscribers.dispatch(&notification);
+                Ok(Some(notification))
             }
             Err(err) => Err(Error::Io(err)),
             _ => unreachable!(),
@@ -189,11 +509,13 @@ impl<'a> FallibleIterator for TimeoutIter<'a> {
 
         match conn.read_message_with_notification_timeout(self.timeout) {
             Ok(Some(Backend::NotificationResponse { process_id, channel, payload })) => {
-                Ok(Some(Notification {
+                let notification = Notification {
                     process_id: process_id,
                     channel: channel,
                     payload: payload,
-                }))
+                };
+                conn.subscribers.dispatch(&notification);
+                Ok(Some(notification))
             }
             Ok(None) => Ok(None),
             Err(err) => Err(Error::Io(err)),
@@ -205,3 +527,737 @@ impl<'a> FallibleIterator for TimeoutIter<'a> {
         (self.conn.conn.borrow().notifications.len(), None)
     }
 }
+
+/// A validated, non-negative timeout computed from an absolute deadline.
+///
+/// Naively subtracting `Instant::now()` from a deadline that has already
+/// passed would underflow; `PollTimeout` clamps that case to a
+/// zero-duration, immediate, non-blocking check instead of panicking or
+/// wrapping around to a huge duration.
+#[derive(Clone, Copy, Debug)]
+pub struct PollTimeout(Duration);
+
+impl PollTimeout {
+    /// Computes the time remaining until `deadline`, clamped to zero if it
+    /// has already passed.
+    pub fn until(deadline: Instant) -> PollTimeout {
+        let now = Instant::now();
+        if deadline <= now {
+            PollTimeout(Duration::from_secs(0))
+        } else {
+            PollTimeout(deadline - now)
+        }
+    }
+
+    /// Returns the remaining duration, guaranteed to be non-negative.
+    pub fn duration(&self) -> Duration {
+        self.0
+    }
+
+    /// Determines if this timeout requires no waiting at all.
+    pub fn is_immediate(&self) -> bool {
+        self.0 == Duration::from_secs(0)
+    }
+}
+
+/// An iterator over notifications which polls until one is received or an
+/// absolute deadline passes.
+///
+/// On a wakeup that doesn't deliver a `NotificationResponse` (a read that
+/// comes back empty, or a protocol message of another kind), the iterator
+/// recomputes the time remaining until `deadline` and keeps polling,
+/// rather than returning `None` after a single read the way
+/// `TimeoutIter::next()` does.
+pub struct DeadlineIter<'a> {
+    conn: &'a Connection,
+    deadline: Instant,
+}
+
+impl<'a> FallibleIterator for DeadlineIter<'a> {
+    type Item = Notification;
+    type Error = Error;
+
+    fn next(&mut self) -> Result<Option<Notification>> {
+        loop {
+            let mut conn = self.conn.conn.borrow_mut();
+
+            if let Some(notification) = conn.notifications.pop_front() {
+                return Ok(Some(notification));
+            }
+
+            if conn.is_desynchronized() {
+                return Err(Error::Io(desynchronized()));
+            }
+
+            let remaining = PollTimeout::until(self.deadline);
+
+            match conn.read_message_with_notification_timeout(remaining.duration()) {
+                Ok(Some(Backend::NotificationResponse { process_id, channel, payload })) => {
+                    let notification = Notification {
+                        process_id: process_id,
+                        channel: channel,
+                        payload: payload,
+                    };
+                    conn.subscribers.dispatch(&notification);
+                    return Ok(Some(notification));
+                }
+                Ok(None) => {
+                    if remaining.is_immediate() {
+                        return Ok(None);
+                    }
+                    // Woken without a notification but time remains; loop
+                    // around and poll again with the recomputed remainder.
+                }
+                // Some other protocol message woke us; poll again with the
+                // recomputed remainder rather than returning early.
+                Ok(Some(_)) => {}
+                Err(err) => return Err(Error::Io(err)),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.conn.conn.borrow().notifications.len(), None)
+    }
+}
+
+/// A `Stream` over notifications that never blocks a thread.
+///
+/// Instead of blocking, `poll` parks at most one helper thread per `Stream`
+/// on the connection's socket becoming readable (via `poll(2)`) and wakes
+/// the current task when it does, matching the non-blocking behaviour of
+/// `Iter` but without requiring the caller to poll it from a loop by hand.
+/// Only available on Unix, since it relies on the socket's raw file
+/// descriptor.
+#[cfg(all(unix, feature = "with-futures"))]
+pub struct Stream<'a> {
+    conn: &'a Connection,
+    waiter: Arc<StreamWaiter>,
+}
+
+/// State shared between a `Stream` and its (at most one) parked helper
+/// thread.
+///
+/// Without this, every `poll()` call that returns `NotReady` would spawn
+/// its own thread parked on the same fd, leaking one thread per call under
+/// combinators like `select!`/`join!` that poll repeatedly before a
+/// `Stream` becomes ready. Instead, at most one thread is ever parked at a
+/// time; later `poll()` calls just update `task` so the existing thread
+/// wakes the most recent task. `cancel` lets `Drop` wake a still-parked
+/// thread immediately when the `Stream` goes away, rather than leaving it
+/// blocked in `poll(2)` on a socket fd that could be reused by the OS for
+/// an unrelated resource once the connection closes.
+#[cfg(all(unix, feature = "with-futures"))]
+struct StreamWaiter {
+    task: Mutex<Option<Task>>,
+    parked: AtomicBool,
+    cancel: SelfPipe,
+}
+
+#[cfg(all(unix, feature = "with-futures"))]
+impl<'a> FuturesStream for Stream<'a> {
+    type Item = Notification;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Notification>, Error> {
+        let mut conn = self.conn.conn.borrow_mut();
+
+        if let Some(notification) = conn.notifications.pop_front() {
+            return Ok(Async::Ready(Some(notification)));
+        }
+
+        if conn.is_desynchronized() {
+            return Err(Error::Io(desynchronized()));
+        }
+
+        match conn.read_message_with_notification_nonblocking() {
+            Ok(Some(Backend::NotificationResponse { process_id, channel, payload })) => {
+                let notification = Notification {
+                    process_id: process_id,
+                    channel: channel,
+                    payload: payload,
+                };
+                conn.subscribers.dispatch(&notification);
+                Ok(Async::Ready(Some(notification)))
+            }
+            Ok(None) => {
+                // No message was ready. Record the current task so
+                // whichever thread is (or becomes) parked on this
+                // connection's readability wakes it, without spawning a
+                // second thread if one is parked already.
+                *self.waiter.task.lock().unwrap() = Some(task::current());
+                if !self.waiter.parked.swap(true, Ordering::SeqCst) {
+                    park_until_readable(conn.as_raw_fd(), self.waiter.clone());
+                }
+                Ok(Async::NotReady)
+            }
+            Err(err) => Err(Error::Io(err)),
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(all(unix, feature = "with-futures"))]
+impl<'a> Drop for Stream<'a> {
+    fn drop(&mut self) {
+        // Wake a parked thread immediately rather than leaving it blocked
+        // in `poll(2)` on a socket fd that may outlive this `Stream` only
+        // to be reused for something else once the connection closes.
+        if self.waiter.parked.load(Ordering::SeqCst) {
+            let byte = [1u8];
+            unsafe {
+                libc::write(self.waiter.cancel.write.0, byte.as_ptr() as *const _, 1);
+            }
+        }
+    }
+}
+
+/// Spawns a helper thread that blocks in `poll(2)` on `fd` or `waiter`'s
+/// cancel pipe becoming readable, then either wakes `waiter`'s task or
+/// exits quietly if cancelled.
+///
+/// This is the bridge between the connection's blocking socket and the
+/// futures task system: at most one thread is ever parked per `Stream`,
+/// and it exits as soon as the socket has something to read, the poll
+/// fails, or the owning `Stream` is dropped and cancels it.
+#[cfg(all(unix, feature = "with-futures"))]
+fn park_until_readable(fd: RawFd, waiter: Arc<StreamWaiter>) {
+    thread::spawn(move || {
+        let cancel_fd = waiter.cancel.read.0;
+
+        loop {
+            let mut fds = [
+                libc::pollfd { fd: fd, events: libc::POLLIN, revents: 0 },
+                libc::pollfd { fd: cancel_fd, events: libc::POLLIN, revents: 0 },
+            ];
+
+            if unsafe { libc::poll(fds.as_mut_ptr(), 2, -1) } < 0 {
+                if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                break;
+            }
+
+            if fds[1].revents != 0 {
+                // Cancelled by `Stream::drop`; the connection may already
+                // be gone, so stop here without touching `fd` again.
+                return;
+            }
+
+            if fds[0].revents != 0 {
+                break;
+            }
+        }
+
+        waiter.parked.store(false, Ordering::SeqCst);
+        if let Some(task) = waiter.task.lock().unwrap().take() {
+            task.notify();
+        }
+    });
+}
+
+/// The action a `Subscriber` takes when a new notification arrives and its
+/// buffer is already full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Discard the oldest buffered notification to make room for the new
+    /// one.
+    DropOldest,
+    /// Keep the existing buffer contents and report an error the next time
+    /// the subscriber is read.
+    Error,
+}
+
+/// The registry of `Subscriber`s for a single connection.
+///
+/// A connection owns one of these; every notification drained off the wire
+/// is cloned into each subscriber whose channel set contains it, so a
+/// notification delivered to one subscriber remains available to the
+/// others.
+#[derive(Default)]
+pub struct SubscriberRegistry {
+    next_id: u64,
+    entries: Vec<SubscriberEntry>,
+}
+
+struct SubscriberEntry {
+    id: u64,
+    channels: HashSet<String>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    buffer: VecDeque<Notification>,
+    overflowed: bool,
+}
+
+impl SubscriberRegistry {
+    fn register(
+        &mut self,
+        channels: HashSet<String>,
+        capacity: usize,
+        policy: OverflowPolicy,
+    ) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.push(SubscriberEntry {
+            id: id,
+            channels: channels,
+            capacity: capacity,
+            policy: policy,
+            buffer: VecDeque::new(),
+            overflowed: false,
+        });
+        id
+    }
+
+    fn unregister(&mut self, id: u64) {
+        self.entries.retain(|entry| entry.id != id);
+    }
+
+    /// Clones `notification` into every subscriber whose channel set
+    /// matches, applying each subscriber's overflow policy if its buffer is
+    /// full.
+    ///
+    /// Every code path in this module that reads a `NotificationResponse`
+    /// directly off the wire (`Iter`, `BlockingIter`, `TimeoutIter`,
+    /// `DeadlineIter`, `Stream`, and `Notifications::barrier`) calls this
+    /// immediately, before the notification is returned to the caller or,
+    /// in `barrier`'s case, pushed onto the shared FIFO those iterators
+    /// read from, so subscribers see every such notification
+    /// regardless of whether anything else ever reads that FIFO. A
+    /// notification popped back off the FIFO by `pop_front` is never
+    /// re-dispatched, since it already went through here once on ingestion.
+    pub fn dispatch(&mut self, notification: &Notification) {
+        for entry in &mut self.entries {
+            if !entry.channels.contains(&notification.channel) {
+                continue;
+            }
+
+            if entry.buffer.len() >= entry.capacity {
+                match entry.policy {
+                    OverflowPolicy::DropOldest => {
+                        entry.buffer.pop_front();
+                    }
+                    OverflowPolicy::Error => {
+                        entry.overflowed = true;
+                        continue;
+                    }
+                }
+            }
+
+            entry.buffer.push_back(notification.clone());
+        }
+    }
+
+    fn entry_mut(&mut self, id: u64) -> &mut SubscriberEntry {
+        self.entries
+            .iter_mut()
+            .find(|entry| entry.id == id)
+            .expect("subscriber entry missing from registry")
+    }
+}
+
+/// A handle that receives a copy of every notification raised on a set of
+/// channels.
+///
+/// Obtained via `Notifications::subscribe`. Multiple subscribers may be
+/// created from the same connection; each maintains its own bounded ring
+/// buffer independent of the others and of the plain `iter`/`blocking_iter`
+/// FIFO.
+pub struct Subscriber<'conn> {
+    conn: &'conn Connection,
+    id: u64,
+}
+
+impl<'conn> fmt::Debug for Subscriber<'conn> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Subscriber")
+            .field("pending", &self.len())
+            .finish()
+    }
+}
+
+impl<'conn> Subscriber<'conn> {
+    /// Returns the number of notifications currently buffered for this
+    /// subscriber.
+    pub fn len(&self) -> usize {
+        let mut conn = self.conn.conn.borrow_mut();
+        conn.subscribers.entry_mut(self.id).buffer.len()
+    }
+
+    /// Determines if there are any notifications buffered for this
+    /// subscriber.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the bounds on the number of notifications this subscriber
+    /// can report, in the style of `Iterator::size_hint`.
+    pub fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len(), None)
+    }
+
+    /// Pops the oldest buffered notification for this subscriber, if any.
+    ///
+    /// Returns `Error::Io` if the subscriber's `OverflowPolicy::Error` was
+    /// triggered by a notification that arrived while the buffer was full;
+    /// the overflow flag is cleared once reported.
+    pub fn try_next(&mut self) -> Result<Option<Notification>> {
+        let mut conn = self.conn.conn.borrow_mut();
+        let entry = conn.subscribers.entry_mut(self.id);
+
+        if entry.overflowed {
+            entry.overflowed = false;
+            return Err(Error::Io(desynchronized()));
+        }
+
+        Ok(entry.buffer.pop_front())
+    }
+}
+
+impl<'conn> Drop for Subscriber<'conn> {
+    fn drop(&mut self) {
+        self.conn.conn.borrow_mut().subscribers.unregister(self.id);
+    }
+}
+
+/// One end of a self-pipe, closed automatically when dropped.
+#[cfg(unix)]
+struct PipeEnd(RawFd);
+
+#[cfg(unix)]
+impl Drop for PipeEnd {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+}
+
+/// A cloneable, `Send` handle that can interrupt an in-progress
+/// `BlockingIter::next()` call.
+///
+/// See `BlockingIter::interrupt_handle` for how it's obtained. Only
+/// available on Unix, since it's implemented with the self-pipe trick.
+#[cfg(unix)]
+#[derive(Clone)]
+pub struct NotificationInterrupt {
+    write_fd: Arc<PipeEnd>,
+}
+
+#[cfg(unix)]
+impl NotificationInterrupt {
+    /// Wakes up an in-progress or future call to the associated
+    /// `BlockingIter::next()`.
+    ///
+    /// This is safe to call from a different thread than the one blocked in
+    /// `next()`, and may be called any number of times over the handle's
+    /// lifetime.
+    pub fn interrupt(&self) {
+        let byte = [1u8];
+        unsafe {
+            libc::write(self.write_fd.0, byte.as_ptr() as *const _, 1);
+        }
+    }
+}
+
+/// Creates a non-blocking pipe, returning its `(read, write)` file
+/// descriptors.
+#[cfg(unix)]
+fn create_self_pipe() -> io::Result<(RawFd, RawFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    for &fd in &fds {
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL, 0) };
+        unsafe {
+            libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+        }
+    }
+
+    Ok((fds[0], fds[1]))
+}
+
+/// Drains every byte currently available on a self-pipe's read end so that
+/// it doesn't immediately re-trigger the next poll.
+#[cfg(unix)]
+fn drain_pipe(fd: RawFd) {
+    let mut buf = [0u8; 64];
+    loop {
+        let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut _, buf.len()) };
+        if n <= 0 {
+            break;
+        }
+    }
+}
+
+/// A factory for fresh, already-authenticated connections.
+///
+/// This is the seam a connection pool plugs into: rather than tying a
+/// `NotificationSource` to one physical `Connection`, it asks an
+/// implementation of this trait for a new one whenever the old one drops
+/// or desynchronizes.
+pub trait ConnectionSource {
+    /// Acquires a connection to issue `LISTEN` statements against and read
+    /// notifications from.
+    fn acquire(&self) -> Result<Connection>;
+}
+
+/// An event delivered by a `NotificationSource`.
+#[derive(Clone, Debug)]
+pub enum SourceEvent {
+    /// A notification raised on one of the source's subscribed channels.
+    Notification(Notification),
+    /// The source just re-established its connection and replayed its
+    /// `LISTEN` set. Notifications raised while disconnected were not
+    /// observed, so callers that need gap-free delivery should treat this
+    /// as a point to resynchronize their own state.
+    Resynchronized,
+}
+
+/// A long-lived LISTEN subscription manager that survives the individual
+/// `Connection`s backing it.
+///
+/// Plain `Notifications` are tied to one live `Connection`: if that
+/// connection drops or is recycled by a pool, every `LISTEN` registration
+/// is silently lost. A `NotificationSource` instead records the set of
+/// channels a caller has subscribed to and, whenever its connection is
+/// gone, acquires a fresh one via `ConnectionSource` and re-issues the
+/// corresponding `LISTEN` statements before resuming delivery.
+pub struct NotificationSource<S> {
+    source: S,
+    channels: HashSet<String>,
+    conn: Option<Connection>,
+}
+
+impl<S> NotificationSource<S>
+where
+    S: ConnectionSource,
+{
+    /// Creates a source with no channels subscribed and no connection yet
+    /// acquired; the first call to `next` will connect.
+    pub fn new(source: S) -> NotificationSource<S> {
+        NotificationSource {
+            source: source,
+            channels: HashSet::new(),
+            conn: None,
+        }
+    }
+
+    /// Subscribes to `channel`, issuing `LISTEN` immediately if currently
+    /// connected, and remembering it so it's replayed after any future
+    /// reconnect.
+    ///
+    /// `channel` is recorded in the subscription set before the live
+    /// `LISTEN` is attempted, so a transient failure (e.g. the pooled
+    /// connection just died) doesn't silently drop the subscription: the
+    /// next reconnect will still replay it.
+    pub fn listen(&mut self, channel: &str) -> Result<()> {
+        self.channels.insert(channel.to_owned());
+
+        if let Some(ref conn) = self.conn {
+            conn.execute(&format!("LISTEN {}", quote_identifier(channel)), &[])?;
+        }
+
+        Ok(())
+    }
+
+    /// Unsubscribes from `channel`, issuing `UNLISTEN` immediately if
+    /// currently connected.
+    pub fn unlisten(&mut self, channel: &str) -> Result<()> {
+        self.channels.remove(channel);
+        if let Some(ref conn) = self.conn {
+            conn.execute(&format!("UNLISTEN {}", quote_identifier(channel)), &[])?;
+        }
+        Ok(())
+    }
+
+    /// Returns the next notification event, transparently reconnecting and
+    /// replaying the `LISTEN` set if the underlying connection was lost.
+    ///
+    /// A reconnect produces exactly one `SourceEvent::Resynchronized`
+    /// before any buffered notifications from the new connection.
+    pub fn next(&mut self) -> Result<SourceEvent> {
+        loop {
+            if self.conn.is_none() {
+                self.reconnect()?;
+                return Ok(SourceEvent::Resynchronized);
+            }
+
+            let desynchronized = {
+                let conn = self.conn.as_ref().unwrap();
+                conn.conn.borrow().is_desynchronized()
+            };
+
+            if desynchronized {
+                self.conn = None;
+                continue;
+            }
+
+            let notification = {
+                let conn = self.conn.as_ref().unwrap();
+                conn.notifications().blocking_iter().next()
+            };
+
+            match notification {
+                Ok(Some(notification)) => return Ok(SourceEvent::Notification(notification)),
+                Ok(None) => continue,
+                Err(_) => {
+                    // The connection died mid-read; drop it so the next
+                    // iteration reconnects and replays the LISTEN set.
+                    self.conn = None;
+                }
+            }
+        }
+    }
+
+    fn reconnect(&mut self) -> Result<()> {
+        let conn = self.source.acquire()?;
+
+        for channel in &self.channels {
+            conn.execute(&format!("LISTEN {}", quote_identifier(channel)), &[])?;
+        }
+
+        self.conn = Some(conn);
+        Ok(())
+    }
+}
+
+/// Quotes `name` for use as a SQL identifier, doubling any embedded double
+/// quotes.
+///
+/// `listen`/`unlisten`/`reconnect` interpolate channel names directly into
+/// statement text rather than binding them as query parameters, since
+/// `LISTEN`/`UNLISTEN` take an identifier, not a literal; quoting here is
+/// what keeps a channel name like `x; DROP TABLE t; --` from being executed
+/// as anything other than a (nonexistent) channel named that.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        quote_identifier, Connection, ConnectionSource, Notification, NotificationSource,
+        OverflowPolicy, PollTimeout, Result, SubscriberRegistry,
+    };
+    use std::time::{Duration, Instant};
+
+    /// A `ConnectionSource` whose `acquire` is never expected to be called,
+    /// for tests that only exercise `NotificationSource` behavior that
+    /// doesn't require a live connection.
+    struct NeverConnects;
+
+    impl ConnectionSource for NeverConnects {
+        fn acquire(&self) -> Result<Connection> {
+            unreachable!("this test shouldn't need to acquire a connection")
+        }
+    }
+
+    #[test]
+    fn listen_records_channel_even_before_any_connection_is_acquired() {
+        let mut source = NotificationSource::new(NeverConnects);
+        source.listen("a").unwrap();
+        assert!(source.channels.contains("a"));
+    }
+
+    #[test]
+    fn unlisten_forgets_a_channel_that_was_never_connected() {
+        let mut source = NotificationSource::new(NeverConnects);
+        source.listen("a").unwrap();
+        source.unlisten("a").unwrap();
+        assert!(!source.channels.contains("a"));
+    }
+
+    #[test]
+    fn quote_identifier_wraps_plain_names_in_double_quotes() {
+        assert_eq!(quote_identifier("my_channel"), "\"my_channel\"");
+    }
+
+    #[test]
+    fn quote_identifier_escapes_embedded_double_quotes() {
+        assert_eq!(quote_identifier("x\"; DROP TABLE t; --"), "\"x\"\"; DROP TABLE t; --\"");
+    }
+
+    #[test]
+    fn poll_timeout_clamps_past_deadline_to_zero() {
+        let past = Instant::now() - Duration::from_secs(1);
+        let timeout = PollTimeout::until(past);
+        assert!(timeout.is_immediate());
+        assert_eq!(timeout.duration(), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn poll_timeout_reflects_remaining_time_for_future_deadline() {
+        let future = Instant::now() + Duration::from_secs(60);
+        let timeout = PollTimeout::until(future);
+        assert!(!timeout.is_immediate());
+        assert!(timeout.duration() <= Duration::from_secs(60));
+        assert!(timeout.duration() > Duration::from_secs(0));
+    }
+
+    fn notification(channel: &str) -> Notification {
+        Notification {
+            process_id: 1,
+            channel: channel.to_owned(),
+            payload: String::new(),
+        }
+    }
+
+    #[test]
+    fn dispatch_fans_out_to_matching_subscribers_only() {
+        let mut registry = SubscriberRegistry::default();
+        let a = registry.register(
+            vec!["a".to_owned()].into_iter().collect(),
+            8,
+            OverflowPolicy::DropOldest,
+        );
+        let b = registry.register(
+            vec!["b".to_owned()].into_iter().collect(),
+            8,
+            OverflowPolicy::DropOldest,
+        );
+
+        registry.dispatch(&notification("a"));
+
+        assert_eq!(registry.entry_mut(a).buffer.len(), 1);
+        assert_eq!(registry.entry_mut(b).buffer.len(), 0);
+    }
+
+    #[test]
+    fn dispatch_drops_oldest_on_overflow() {
+        let mut registry = SubscriberRegistry::default();
+        let id = registry.register(
+            vec!["a".to_owned()].into_iter().collect(),
+            2,
+            OverflowPolicy::DropOldest,
+        );
+
+        registry.dispatch(&Notification { process_id: 1, channel: "a".to_owned(), payload: "1".to_owned() });
+        registry.dispatch(&Notification { process_id: 2, channel: "a".to_owned(), payload: "2".to_owned() });
+        registry.dispatch(&Notification { process_id: 3, channel: "a".to_owned(), payload: "3".to_owned() });
+
+        let entry = registry.entry_mut(id);
+        assert_eq!(entry.buffer.len(), 2);
+        assert_eq!(entry.buffer[0].payload, "2");
+        assert_eq!(entry.buffer[1].payload, "3");
+    }
+
+    #[test]
+    fn dispatch_flags_overflow_under_error_policy() {
+        let mut registry = SubscriberRegistry::default();
+        let id = registry.register(
+            vec!["a".to_owned()].into_iter().collect(),
+            1,
+            OverflowPolicy::Error,
+        );
+
+        registry.dispatch(&notification("a"));
+        registry.dispatch(&notification("a"));
+
+        let entry = registry.entry_mut(id);
+        assert_eq!(entry.buffer.len(), 1);
+        assert!(entry.overflowed);
+    }
+}